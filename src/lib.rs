@@ -2,14 +2,18 @@
 
 use anyhow::Result;
 use itertools::Itertools;
-use log;
 use mdbook::{
     book::Book,
     preprocess::{Preprocessor, PreprocessorContext},
     BookItem,
 };
-use pulldown_cmark::{Event, Parser};
+use pulldown_cmark::{CowStr, Event, Parser, Tag};
 use pulldown_cmark_to_cmark::cmark;
+use regex::Regex;
+use serde::Deserialize;
+
+const COMMENT_START: &str = "<!--";
+const COMMENT_END: &str = "-->";
 
 pub struct NoCommentPreprocessor;
 
@@ -18,17 +22,52 @@ impl Preprocessor for NoCommentPreprocessor {
         "nocomment-preprocessor"
     }
 
-    fn run(&self, _ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        let config = Config::from_context(ctx)?;
+        let mut stripped_comments: Vec<StrippedComment> = vec![];
         book.for_each_mut(|item: &mut BookItem| {
             if let BookItem::Chapter(ref mut chapter) = *item {
                 let content_events =
-                    Parser::new_ext(&chapter.content, pulldown_cmark::Options::empty());
-                let events = remove_comment(content_events);
+                    Parser::new_ext(&chapter.content, pulldown_cmark::Options::empty())
+                        .into_offset_iter();
+                let (events, stripped) = remove_comment(content_events, &config, &ctx.renderer);
                 let mut buf = String::with_capacity(chapter.content.len());
                 cmark(events, &mut buf).unwrap();
                 chapter.content = buf;
+
+                if !stripped.is_empty() {
+                    let bytes_removed: usize = stripped.iter().map(|(_, text)| text.len()).sum();
+                    // Preprocessors run as a subprocess over stdin/stdout with no logger
+                    // backend installed (see main.rs), so log::info! here would never
+                    // reach the user; eprintln! is what actually surfaces this summary.
+                    eprintln!(
+                        "nocomment: removed {} comment(s) ({} bytes) from {}",
+                        stripped.len(),
+                        bytes_removed,
+                        chapter.name,
+                    );
+                    stripped_comments.extend(stripped.into_iter().map(|(offset, text)| {
+                        StrippedComment {
+                            chapter: chapter.name.clone(),
+                            offset,
+                            text,
+                        }
+                    }));
+                }
             }
         });
+
+        let total_bytes: usize = stripped_comments.iter().map(|c| c.text.len()).sum();
+        eprintln!(
+            "nocomment: removed {} comment(s) ({} bytes) in total",
+            stripped_comments.len(),
+            total_bytes,
+        );
+
+        if let Some(dump_to) = &config.dump_to {
+            dump_stripped_comments(dump_to, &stripped_comments)?;
+        }
+
         Ok(book)
     }
 
@@ -37,27 +76,345 @@ impl Preprocessor for NoCommentPreprocessor {
     }
 }
 
-fn remove_comment<'a>(events: impl Iterator<Item = Event<'a>>) -> impl Iterator<Item = Event<'a>> {
-    const COMMENT_START: &str = "<!--";
-    const COMMENT_END: &str = "-->";
-    let mut filtered = vec![];
+/// Raw shape of the `[preprocessor.nocomment]` table in `book.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RawConfig {
+    #[serde(default)]
+    keep_patterns: Vec<String>,
+    #[serde(default)]
+    markers: Vec<String>,
+    /// Whether to strip comment-like text inside fenced/indented code blocks too.
+    /// Defaults to `false`: code blocks are protected out of the box, matching the common
+    /// HTML/markdown-tutorial case of `<!-- -->` literally appearing in a code sample.
+    #[serde(default = "default_strip_in_code")]
+    strip_in_code: bool,
+    #[serde(default = "default_keep_next_marker")]
+    keep_next_marker: String,
+    #[serde(default)]
+    dump_to: Option<String>,
+}
+
+fn default_strip_in_code() -> bool {
+    false
+}
+
+fn default_keep_next_marker() -> String {
+    "nocomment:keep-next".to_string()
+}
+
+impl Default for RawConfig {
+    fn default() -> Self {
+        RawConfig {
+            keep_patterns: Vec::new(),
+            markers: Vec::new(),
+            strip_in_code: default_strip_in_code(),
+            keep_next_marker: default_keep_next_marker(),
+            dump_to: None,
+        }
+    }
+}
+
+/// Per-book settings for [`NoCommentPreprocessor`], compiled once in [`Preprocessor::run`]
+/// and threaded into [`remove_comment`].
+struct Config {
+    keep_patterns: Vec<Regex>,
+    markers: Vec<String>,
+    strip_in_code: bool,
+    keep_next_marker: String,
+    dump_to: Option<String>,
+}
+
+/// One HTML comment dropped by [`remove_comment`], recorded for [`dump_stripped_comments`].
+struct StrippedComment {
+    chapter: String,
+    /// Byte offset of the comment's opening `<!--` within the chapter's source markdown.
+    offset: usize,
+    text: String,
+}
+
+/// What [`remove_comment`] should do with a fully-assembled comment.
+enum CommentDecision {
+    /// Drop the comment; it matched no keep rule.
+    Strip,
+    /// Keep the comment verbatim in the output.
+    Keep,
+    /// The comment is the `keep-next` directive itself: drop it, but arm the
+    /// one-shot flag that preserves whichever comment follows.
+    KeepNextDirective,
+}
+
+impl Config {
+    fn from_context(ctx: &PreprocessorContext) -> Result<Self> {
+        let raw: RawConfig = ctx
+            .config
+            .get_deserialized_opt("preprocessor.nocomment")?
+            .unwrap_or_default();
+        let keep_patterns = raw
+            .keep_patterns
+            .iter()
+            .map(|p| {
+                Regex::new(p)
+                    .map_err(|e| anyhow::anyhow!("invalid keep-patterns regex {:?}: {}", p, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Config {
+            keep_patterns,
+            markers: raw.markers,
+            strip_in_code: raw.strip_in_code,
+            keep_next_marker: raw.keep_next_marker,
+            dump_to: raw.dump_to,
+        })
+    }
+
+    /// Classify a fully-assembled comment, including its `<!--`/`-->` delimiters.
+    fn classify(&self, comment: &str) -> CommentDecision {
+        let inner_raw = comment
+            .trim()
+            .trim_start_matches("<!--")
+            .trim_end_matches("-->");
+        // `<!--! ... -->`: the bang marks the comment as always kept, e.g. license headers.
+        if inner_raw.starts_with('!') {
+            return CommentDecision::Keep;
+        }
+        let inner = inner_raw.trim();
+        if inner == self.keep_next_marker {
+            return CommentDecision::KeepNextDirective;
+        }
+        if self.keep_patterns.iter().any(|re| re.is_match(inner))
+            || self.markers.iter().any(|m| inner.contains(m.as_str()))
+        {
+            return CommentDecision::Keep;
+        }
+        CommentDecision::Strip
+    }
+}
+
+/// One comment or plain-text span of a larger blob, as split out by [`split_comments`].
+enum Segment<'a> {
+    Text(&'a str),
+    Comment(&'a str),
+}
+
+/// Split a chunk of assembled markdown source into an ordered sequence of `<!-- ... -->`
+/// comments and the plain text between them. pulldown-cmark coalesces adjacent comments
+/// (and any text between them, since `<!-- -->` starts a CommonMark HTML block that runs
+/// to the end of the line holding its `-->`) into a single `Html`/`Text` event, so callers must
+/// re-split that blob before classifying anything in it: otherwise e.g. a `keep-next` or
+/// bang comment sitting right next to another comment is seen as one unrecognizable blob
+/// instead of two distinct comments.
+fn split_comments(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(COMMENT_START) {
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        let from_start = &rest[start..];
+        match from_start.find(COMMENT_END) {
+            Some(end) => {
+                let comment_len = end + COMMENT_END.len();
+                segments.push(Segment::Comment(&from_start[..comment_len]));
+                rest = &from_start[comment_len..];
+            }
+            None => {
+                // A blob may hold an opening `<!--` with no closing `-->` after it (e.g. a
+                // code-block line, or text trailing a comment on the same line); keep the
+                // dangling `<!--` as text rather than lose it.
+                segments.push(Segment::Text(from_start));
+                rest = "";
+            }
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+/// Mutable state threaded through [`process_removal`] as events are walked: the
+/// `nocomment:only`/`except`/`end` condition stack, the one-shot `keep-next` flag, the
+/// output events accumulated so far, and the stripped-comment sidecar. Bundled into one
+/// struct so `process_removal` takes a single `&mut` context instead of one parameter per
+/// field (`clippy::too_many_arguments`).
+struct RemovalState<'a> {
+    conditions: Vec<ConditionFrame<'a>>,
+    skip_next_removal: bool,
+    filtered: Vec<Event<'a>>,
+    stripped: Vec<(usize, String)>,
+}
+
+/// Resolve one fully-assembled blob of source text — which may contain several adjacent
+/// comments and the plain text between them (see [`split_comments`]) — against the
+/// `nocomment:only`/`except`/`end` directive stack and [`Config::classify`], pushing the
+/// result onto whichever destination [`condition_out`] selects. Comments that end up
+/// stripped are recorded into `state.stripped` (paired with the byte offset of their
+/// opening `<!--`) for [`Preprocessor::run`] to report and optionally sidecar-dump.
+///
+/// Each comment is checked against the directive stack *individually*: a single coalesced
+/// blob can hold both a directive and the content it gates (e.g.
+/// `<!-- nocomment:only html -->kept<!-- nocomment:end -->`), so directives can't be
+/// detected by looking at the blob as a whole the way [`handle_directive`] used to.
+fn process_removal<'a>(
+    removal: &str,
+    offset: usize,
+    config: &Config,
+    renderer: &str,
+    state: &mut RemovalState<'a>,
+) {
+    let mut pos = offset;
+    for segment in split_comments(removal) {
+        match segment {
+            Segment::Text(text) => {
+                condition_out(&mut state.conditions, &mut state.filtered)
+                    .push(Event::Text(CowStr::from(text.to_string())));
+                pos += text.len();
+            }
+            Segment::Comment(comment) => {
+                if !handle_directive(&mut state.conditions, renderer, comment) {
+                    match config.classify(comment) {
+                        CommentDecision::KeepNextDirective => {
+                            state.skip_next_removal = true;
+                            log::debug!("keep-next directive: {}", comment);
+                        }
+                        _ if state.skip_next_removal => {
+                            condition_out(&mut state.conditions, &mut state.filtered)
+                                .push(Event::Html(CowStr::from(comment.to_string())));
+                            state.skip_next_removal = false;
+                        }
+                        CommentDecision::Keep => {
+                            condition_out(&mut state.conditions, &mut state.filtered)
+                                .push(Event::Html(CowStr::from(comment.to_string())));
+                        }
+                        CommentDecision::Strip => {
+                            log::debug!("Comment: {}", comment);
+                            state.stripped.push((pos, comment.to_string()));
+                        }
+                    }
+                }
+                pos += comment.len();
+            }
+        }
+    }
+}
+
+/// Write every collected [`StrippedComment`] to `path`, grouped by chapter and ordered by
+/// source position, so teams can review or recover hidden review notes after the fact.
+fn dump_stripped_comments(path: &str, comments: &[StrippedComment]) -> Result<()> {
+    use std::collections::BTreeMap;
+
+    let mut by_chapter: BTreeMap<&str, Vec<(usize, &str)>> = BTreeMap::new();
+    for comment in comments {
+        by_chapter
+            .entry(comment.chapter.as_str())
+            .or_default()
+            .push((comment.offset, comment.text.as_str()));
+    }
+
+    let mut buf = String::new();
+    for (chapter, mut entries) in by_chapter {
+        entries.sort_by_key(|(offset, _)| *offset);
+        buf.push_str(&format!("## {}\n\n", chapter));
+        for (offset, text) in entries {
+            buf.push_str(&format!(
+                "### byte offset {}\n\n```\n{}\n```\n\n",
+                offset, text
+            ));
+        }
+    }
+    std::fs::write(path, buf)?;
+    Ok(())
+}
+
+/// A `<!-- nocomment:only ... -->` / `<!-- nocomment:except ... -->` / `<!-- nocomment:end -->`
+/// renderer-conditional directive, parsed from a fully-assembled comment body.
+enum Directive {
+    Only(Vec<String>),
+    Except(Vec<String>),
+    End,
+}
+
+impl Directive {
+    /// Parse a comment's inner text (with `<!--`/`-->` and surrounding whitespace stripped),
+    /// or `None` if it isn't one of the renderer-conditional directives.
+    fn parse(inner: &str) -> Option<Directive> {
+        if inner == "nocomment:end" {
+            return Some(Directive::End);
+        }
+        if let Some(renderers) = inner.strip_prefix("nocomment:only ") {
+            return Some(Directive::Only(
+                renderers.split_whitespace().map(str::to_string).collect(),
+            ));
+        }
+        if let Some(renderers) = inner.strip_prefix("nocomment:except ") {
+            return Some(Directive::Except(
+                renderers.split_whitespace().map(str::to_string).collect(),
+            ));
+        }
+        None
+    }
+}
+
+/// One entry of the conditional-block stack maintained by [`remove_comment`]. `effective_active`
+/// folds this frame's own renderer match together with every ancestor's `effective_active`, so
+/// a nested `only`/`except` block can never re-enable content an outer block already excluded;
+/// only the frame on top of the stack is consulted, by [`condition_out`]. `buffer` collects
+/// events seen while this frame is suppressed, so an unterminated block can still be recovered
+/// (kept) instead of silently eating the rest of the chapter.
+struct ConditionFrame<'a> {
+    effective_active: bool,
+    buffer: Vec<Event<'a>>,
+}
+
+fn remove_comment<'a>(
+    events: impl Iterator<Item = (Event<'a>, std::ops::Range<usize>)>,
+    config: &Config,
+    renderer: &str,
+) -> (impl Iterator<Item = Event<'a>>, Vec<(usize, String)>) {
+    let mut state = RemovalState {
+        conditions: vec![],
+        skip_next_removal: false,
+        filtered: vec![],
+        stripped: vec![],
+    };
+    let mut in_code_block = false;
     let mut mp = events.multipeek();
-    loop {
-        let current_event = match mp.next() {
-            Some(e) => e,
-            None => break,
-        };
+    while let Some((current_event, current_range)) = mp.next() {
         match current_event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                condition_out(&mut state.conditions, &mut state.filtered).push(current_event);
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                in_code_block = false;
+                condition_out(&mut state.conditions, &mut state.filtered).push(current_event);
+            }
+            // Inline code spans arrive as a single atomic `Code` event (no matching `End`),
+            // so their text never gets split into the `Text`/`Html` sequences the comment
+            // matcher below looks for; pass them through untouched either way.
+            Event::Code(_) => {
+                condition_out(&mut state.conditions, &mut state.filtered).push(current_event)
+            }
+            _ if in_code_block && !config.strip_in_code => {
+                condition_out(&mut state.conditions, &mut state.filtered).push(current_event);
+            }
+            // A fenced/indented code block's contents arrive as a single `Text` event per
+            // line, so a comment inside one (e.g. `<!-- literal comment -->\n`) is never
+            // split into the `"<"` + `"!--..."` pair the prose matcher below looks for.
+            Event::Text(ref t) if in_code_block && t.contains(COMMENT_START) => {
+                process_removal(t, current_range.start, config, renderer, &mut state);
+            }
             Event::Text(ref t1) if t1.as_ref().eq("<") => {
+                let offset = current_range.start;
                 let next = mp.peek();
                 match next {
-                    Some(Event::Text(ref t2)) if t2.starts_with("!--") => {
+                    Some((Event::Text(ref t2), _)) if t2.starts_with("!--") => {
                         let mut removal = t1.to_string();
                         removal.push_str(t2);
                         // Ended at current event
                         if t2.trim_end().ends_with(COMMENT_END) {
                             mp.next();
-                            log::debug!("Comment: {}", removal);
+                            process_removal(&removal, offset, config, renderer, &mut state);
                             continue;
                         }
                         // Peek text event for COMMENT_END
@@ -66,7 +423,7 @@ fn remove_comment<'a>(events: impl Iterator<Item = Event<'a>>) -> impl Iterator<
                         loop {
                             let nn = mp.peek();
                             match nn {
-                                Some(Event::Text(ref c)) => {
+                                Some((Event::Text(ref c), _)) => {
                                     removal.push_str(c);
                                     count += 1;
                                     if c.trim_end().ends_with(COMMENT_END) {
@@ -87,53 +444,123 @@ fn remove_comment<'a>(events: impl Iterator<Item = Event<'a>>) -> impl Iterator<
                             for _ in 0..=count {
                                 mp.next();
                             }
-                            log::debug!("Comment: {}", removal);
+                            process_removal(&removal, offset, config, renderer, &mut state);
                         } else {
-                            filtered.push(current_event)
+                            condition_out(&mut state.conditions, &mut state.filtered)
+                                .push(current_event);
                         }
                     }
-                    _ => filtered.push(current_event),
+                    _ => condition_out(&mut state.conditions, &mut state.filtered)
+                        .push(current_event),
                 };
             }
             Event::Html(ref html) if html.starts_with(COMMENT_START) => {
-                if html.trim_end().ends_with(COMMENT_END) {
-                    // Ended at current event
-                    continue;
-                }
-                let mut removal = vec![html.to_string()];
-                let mut found = false;
-                let mut cnt = 0;
-                loop {
-                    let next = mp.peek();
-                    match next {
-                        Some(Event::Html(ref h)) => {
-                            removal.push(h.to_string());
-                            cnt += 1;
-                            if h.trim_end().ends_with(COMMENT_END) {
-                                found = true;
-                                for _ in 0..cnt {
-                                    mp.next();
+                let offset = current_range.start;
+                let mut removal = html.to_string();
+                // A start-of-line `<!--` HTML block that has trailing content on the same
+                // line (e.g. `<!-- only html -->kept<!-- end -->after`) is coalesced by
+                // pulldown-cmark into one `Html` event that does *not* end in `-->` — the
+                // closing delimiter can be anywhere inside it, not just at the end. Only
+                // keep peeking ahead when this event has no `-->` of its own at all, i.e.
+                // the comment itself is still open and spans further `Html` events.
+                if !removal.contains(COMMENT_END) {
+                    let mut found = false;
+                    let mut cnt = 0;
+                    loop {
+                        let next = mp.peek();
+                        match next {
+                            Some((Event::Html(ref h), _)) => {
+                                removal.push('\n');
+                                removal.push_str(h);
+                                cnt += 1;
+                                if h.contains(COMMENT_END) {
+                                    found = true;
+                                    break;
                                 }
-                                log::debug!("{}", removal.join("\n"));
-                                continue;
                             }
+                            _ => break,
                         }
-                        _ => break,
+                    }
+                    if found {
+                        for _ in 0..cnt {
+                            mp.next();
+                        }
+                    } else {
+                        condition_out(&mut state.conditions, &mut state.filtered)
+                            .push(current_event);
+                        continue;
                     }
                 }
-                if !found {
-                    filtered.push(current_event)
-                }
+                process_removal(&removal, offset, config, renderer, &mut state);
             }
             // Not a comment event, push it as is.
-            _ => filtered.push(current_event),
+            _ => condition_out(&mut state.conditions, &mut state.filtered).push(current_event),
+        }
+    }
+    if !state.conditions.is_empty() {
+        log::warn!(
+            "{} unterminated nocomment:only/except block(s); keeping their content",
+            state.conditions.len()
+        );
+        for frame in state.conditions {
+            state.filtered.extend(frame.buffer);
+        }
+    }
+    (state.filtered.into_iter(), state.stripped)
+}
+
+/// Borrow the destination `process_removal` should write a segment's events into: the
+/// innermost suppressed block's buffer, or the chapter's output.
+fn condition_out<'a, 'b>(
+    conditions: &'b mut [ConditionFrame<'a>],
+    filtered: &'b mut Vec<Event<'a>>,
+) -> &'b mut Vec<Event<'a>> {
+    match conditions.last_mut() {
+        Some(frame) if !frame.effective_active => &mut frame.buffer,
+        _ => filtered,
+    }
+}
+
+/// If `removal` is a `nocomment:only`/`nocomment:except`/`nocomment:end` directive, apply it
+/// to `conditions` and return `true` (the comment is fully consumed either way). Otherwise
+/// return `false` so the caller runs its normal keep/strip handling.
+fn handle_directive(conditions: &mut Vec<ConditionFrame>, renderer: &str, removal: &str) -> bool {
+    let inner = removal
+        .trim()
+        .trim_start_matches("<!--")
+        .trim_end_matches("-->")
+        .trim();
+    let parent_active = conditions.last().is_none_or(|f| f.effective_active);
+    match Directive::parse(inner) {
+        Some(Directive::Only(renderers)) => {
+            let active = renderers.iter().any(|r| r == renderer);
+            conditions.push(ConditionFrame {
+                effective_active: active && parent_active,
+                buffer: vec![],
+            });
+            true
         }
+        Some(Directive::Except(renderers)) => {
+            let active = !renderers.iter().any(|r| r == renderer);
+            conditions.push(ConditionFrame {
+                effective_active: active && parent_active,
+                buffer: vec![],
+            });
+            true
+        }
+        Some(Directive::End) => {
+            // `end` with no matching `only`/`except` is left for future events to pass through.
+            conditions.pop();
+            true
+        }
+        None => false,
     }
-    filtered.into_iter()
 }
 
 #[cfg(test)]
 mod test {
+    use super::Config;
+
     #[test]
     fn remove_comments() {
         // oneline comment (one Html event)
@@ -170,15 +597,144 @@ mod test {
         );
     }
 
-    fn assert_comment_removal(s: &str) {
-        let parser = mdbook::utils::new_cmark_parser(s, false);
+    #[test]
+    fn keep_pattern_preserves_matching_comment() {
+        let mut config = default_config();
+        config.keep_patterns = vec![regex::Regex::new(r"^\s*!").unwrap()];
+        let rendered = render("<!-- !keep this -->", &config, "html");
+        assert!(rendered.contains("!keep this"));
+    }
+
+    #[test]
+    fn marker_preserves_matching_comment() {
+        let mut config = default_config();
+        config.markers = vec!["TODO".to_string()];
+        let rendered = render("<!-- TODO: revisit -->", &config, "html");
+        assert!(rendered.contains("TODO: revisit"));
+    }
+
+    #[test]
+    fn keep_next_directive_preserves_the_following_comment_only() {
+        let config = default_config();
+        let rendered = render(
+            "<!-- nocomment:keep-next --><!-- kept --><!-- dropped -->",
+            &config,
+            "html",
+        );
+        assert!(!rendered.contains("nocomment:keep-next"));
+        assert!(rendered.contains("kept"));
+        assert!(!rendered.contains("dropped"));
+    }
 
-        let events = crate::remove_comment(parser);
+    #[test]
+    fn bang_comment_is_always_kept() {
+        let config = default_config();
+        let rendered = render("<!--! license header -->", &config, "html");
+        assert!(rendered.contains("license header"));
+    }
+
+    #[test]
+    fn only_block_is_kept_for_matching_renderer_and_dropped_otherwise() {
+        let config = default_config();
+        let source = "<!-- nocomment:only html -->kept content<!-- nocomment:end -->";
+        assert!(render(source, &config, "html").contains("kept content"));
+        assert!(!render(source, &config, "epub").contains("kept content"));
+    }
+
+    #[test]
+    fn except_block_is_dropped_for_listed_renderer_and_kept_otherwise() {
+        let config = default_config();
+        let source = "<!-- nocomment:except epub -->kept content<!-- nocomment:end -->";
+        assert!(!render(source, &config, "epub").contains("kept content"));
+        assert!(render(source, &config, "html").contains("kept content"));
+    }
+
+    #[test]
+    fn unterminated_block_keeps_its_content() {
+        let config = default_config();
+        let rendered = render(
+            "<!-- nocomment:only epub -->orphaned content",
+            &config,
+            "html",
+        );
+        assert!(rendered.contains("orphaned content"));
+    }
+
+    #[test]
+    fn nested_block_cannot_override_a_suppressed_ancestor() {
+        let config = default_config();
+        let source = "<!-- nocomment:only epub -->\
+            outer-protected\
+            <!-- nocomment:only html -->\
+            inner-leak\
+            <!-- nocomment:end -->\
+            still-outer\
+            <!-- nocomment:end -->\
+            after";
+        let rendered = render(source, &config, "html");
+        assert!(!rendered.contains("outer-protected"));
+        assert!(!rendered.contains("inner-leak"));
+        assert!(!rendered.contains("still-outer"));
+        assert!(rendered.contains("after"));
+    }
+
+    #[test]
+    fn fenced_code_block_comment_is_protected_by_default() {
+        let config = default_config();
+        let source = "```html\n<!-- literal comment -->\n```\n";
+        let rendered = render(source, &config, "html");
+        assert!(rendered.contains("literal comment"));
+    }
+
+    #[test]
+    fn fenced_code_block_comment_is_stripped_when_strip_in_code_is_enabled() {
+        let mut config = default_config();
+        config.strip_in_code = true;
+        let source = "```html\n<!-- literal comment -->\n```\n";
+        let rendered = render(source, &config, "html");
+        assert!(!rendered.contains("literal comment"));
+    }
+
+    #[test]
+    fn inline_code_span_comment_is_always_protected() {
+        let config = default_config();
+        let rendered = render("`<!-- literal comment -->`", &config, "html");
+        assert!(rendered.contains("literal comment"));
+    }
+
+    #[test]
+    fn stripped_comments_are_collected_but_kept_ones_are_not() {
+        let config = default_config();
+        let parser = mdbook::utils::new_cmark_parser("<!-- drop me --><!--! keep me -->", false)
+            .into_offset_iter();
+        let (_events, stripped) = crate::remove_comment(parser, &config, "html");
+        assert_eq!(stripped, vec![(0, "<!-- drop me -->".to_string())]);
+    }
+
+    fn default_config() -> Config {
+        Config {
+            keep_patterns: vec![],
+            markers: vec![],
+            strip_in_code: false,
+            keep_next_marker: "nocomment:keep-next".to_string(),
+            dump_to: None,
+        }
+    }
+
+    fn render(s: &str, config: &Config, renderer: &str) -> String {
+        let parser = mdbook::utils::new_cmark_parser(s, false).into_offset_iter();
+        let (events, _stripped) = crate::remove_comment(parser, config, renderer);
         let mut buf = String::new();
         pulldown_cmark::html::push_html(&mut buf, events);
+        buf
+    }
+
+    fn assert_comment_removal(s: &str) {
+        let config = default_config();
+        let buf = render(s, &config, "html");
 
         log::debug!("RENDERED: {buf}");
         assert!(!buf.contains("double-hyphen"));
         assert!(!buf.contains("--"));
     }
-}
\ No newline at end of file
+}